@@ -0,0 +1,32 @@
+use anyhow::Result;
+
+pub enum Messages {
+    Capabilities,
+    DeviceInfo,
+    Profiles,
+    GetStreamURI,
+}
+
+/// Stand-in ONVIF client. A real implementation would keep a WS-Discovery
+/// cache and per-device SOAP endpoints; this just reads each camera's stream
+/// URI from `CAM_0_RTSP_URI` / `CAM_1_RTSP_URI` and so on, falling back to
+/// `rtsp://127.0.0.1:554/cam<index>` when the variable is unset.
+pub struct Client;
+
+impl Client {
+    pub async fn new() -> Self {
+        Client
+    }
+
+    pub async fn send(&mut self, msg: Messages, cam_index: usize) -> Result<String> {
+        match msg {
+            Messages::Capabilities | Messages::DeviceInfo | Messages::Profiles => Ok(String::new()),
+            Messages::GetStreamURI => {
+                let env_var = format!("CAM_{cam_index}_RTSP_URI");
+                let uri = std::env::var(&env_var)
+                    .unwrap_or_else(|_| format!("rtsp://127.0.0.1:554/cam{cam_index}"));
+                Ok(uri)
+            }
+        }
+    }
+}
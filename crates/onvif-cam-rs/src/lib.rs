@@ -0,0 +1,11 @@
+/*!
+
+Minimal ONVIF discovery client vendored in-tree for the nvr-rs example.
+Real device discovery (WS-Discovery, SOAP Capabilities/DeviceInfo/Profiles
+calls) is out of scope here - this just gives `nvr-rs` a stand-in client
+with the same shape so the example binary builds and runs against a
+configured stream URI.
+
+*/
+
+pub mod client;
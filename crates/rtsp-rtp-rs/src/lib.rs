@@ -0,0 +1,10 @@
+/*!
+
+See the github repo example for usage details.
+
+*/
+
+mod recorder;
+pub mod rtp;
+mod rtcp;
+pub mod rtsp;
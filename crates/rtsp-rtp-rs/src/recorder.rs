@@ -0,0 +1,203 @@
+use anyhow::Result;
+use mp4::{AvcConfig, Bytes, MediaConfig, Mp4Config, Mp4Sample, Mp4Writer, TrackConfig, TrackType};
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// RTP's video clock rate (RFC 3551: always 90kHz, independent of frame
+/// rate) reused as the MP4 track timescale, so sample durations fall out of
+/// RTP timestamp deltas directly with no unit conversion.
+const TIMESCALE: u32 = 90_000;
+
+/// Muxes the decoded H.264 access units into a standalone MP4 file
+/// alongside the live SDL2 view, so a session can be archived while it's
+/// being watched - the core NVR use case.
+///
+/// Samples are buffered one access unit behind: an access unit's duration
+/// isn't known until the next one's RTP timestamp arrives, so each
+/// `write_sample` call actually flushes the *previous* access unit.
+///
+/// The `mp4` crate only writes the classic `moov`-at-the-end layout -
+/// nothing makes a fragmented (`moof`/`mdat`-per-fragment) MP4 here, so the
+/// file has no valid `moov` box, and isn't playable, until `finish()` runs.
+/// To avoid a crash (power loss, SIGKILL) leaving an unplayable file sitting
+/// at the path a caller expects a finished recording at, samples are
+/// written to a `.partial` sibling path and only renamed to the real path
+/// in `finish()` - so a recording that never finished stays visibly
+/// incomplete (or absent) instead of silently corrupt.
+pub(crate) struct Mp4Recorder {
+    writer: Mp4Writer<File>,
+    final_path: PathBuf,
+    partial_path: PathBuf,
+    track_id: u32,
+    base_timestamp: Option<u32>,
+    pending: Option<PendingSample>,
+}
+
+struct PendingSample {
+    start_time: u64,
+    is_sync: bool,
+    bytes: Vec<u8>,
+}
+
+impl Mp4Recorder {
+    /// Opens a `.partial` sibling of `path` and writes the MP4 header plus
+    /// a single AVC video track whose `avcC` box is seeded from `sps`/`pps`
+    /// (raw NAL payload, no Annex B start code). Call once SPS/PPS are
+    /// cached, i.e. no earlier than the first frame `Rtp` actually decodes.
+    /// `path` itself doesn't exist until `finish()` renames the partial
+    /// file onto it.
+    pub(crate) fn create(path: &Path, width: u16, height: u16, sps: &[u8], pps: &[u8]) -> Result<Self> {
+        let partial_path = partial_path_for(path);
+        let file = File::create(&partial_path)?;
+
+        let config = Mp4Config {
+            major_brand: str::parse("isom")?,
+            minor_version: 512,
+            compatible_brands: vec![
+                str::parse("isom")?,
+                str::parse("iso2")?,
+                str::parse("avc1")?,
+                str::parse("mp41")?,
+            ],
+            timescale: TIMESCALE,
+        };
+        let mut writer = Mp4Writer::write_start(file, &config)?;
+
+        writer.add_track(&TrackConfig {
+            track_type: TrackType::Video,
+            timescale: TIMESCALE,
+            language: "und".to_string(),
+            media_conf: MediaConfig::AvcConfig(AvcConfig {
+                width,
+                height,
+                seq_param_set: sps.to_vec(),
+                pic_param_set: pps.to_vec(),
+            }),
+        })?;
+
+        Ok(Mp4Recorder {
+            writer,
+            final_path: path.to_path_buf(),
+            partial_path,
+            track_id: 1,
+            base_timestamp: None,
+            pending: None,
+        })
+    }
+
+    /// Queues one decoded access unit (`nal_units`: AVCC length-prefixed,
+    /// see `annex_b_to_avcc`) for writing, flushing whichever access unit
+    /// was queued before it now that this one's timestamp gives it a
+    /// duration.
+    pub(crate) fn write_sample(
+        &mut self,
+        rtp_timestamp: u32,
+        is_sync: bool,
+        nal_units: Vec<u8>,
+    ) -> Result<()> {
+        let base = *self.base_timestamp.get_or_insert(rtp_timestamp);
+        let start_time = rtp_timestamp.wrapping_sub(base) as u64;
+
+        if let Some(prev) = self.pending.take() {
+            let duration = start_time.saturating_sub(prev.start_time) as u32;
+            self.writer.write_sample(
+                self.track_id,
+                &Mp4Sample {
+                    start_time: prev.start_time,
+                    duration,
+                    rendering_offset: 0,
+                    is_sync: prev.is_sync,
+                    bytes: Bytes::from(prev.bytes),
+                },
+            )?;
+        }
+
+        self.pending = Some(PendingSample {
+            start_time,
+            is_sync,
+            bytes: nal_units,
+        });
+
+        Ok(())
+    }
+
+    /// Flushes the last buffered access unit, finalizes the MP4 boxes, and
+    /// renames the `.partial` file onto the path `create` was given - only
+    /// past this point does a valid, playable recording exist there.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        if let Some(prev) = self.pending.take() {
+            self.writer.write_sample(
+                self.track_id,
+                &Mp4Sample {
+                    start_time: prev.start_time,
+                    duration: 0,
+                    rendering_offset: 0,
+                    is_sync: prev.is_sync,
+                    bytes: Bytes::from(prev.bytes),
+                },
+            )?;
+        }
+
+        self.writer.write_end()?;
+        std::fs::rename(&self.partial_path, &self.final_path)?;
+        Ok(())
+    }
+}
+
+/// `path` with `.partial` appended to its file name, e.g. `cam0.mp4` ->
+/// `cam0.mp4.partial`.
+fn partial_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(OsString::from(".partial"));
+    path.with_file_name(file_name)
+}
+
+/// Converts an Annex B buffer (one or more start-code-delimited NAL units,
+/// 3- or 4-byte codes) into the length-prefixed form ISO/IEC 14496-15
+/// samples use, dropping SPS/PPS NALs - those are carried by the `avcC` box
+/// instead of repeated in every sample.
+pub(crate) fn annex_b_to_avcc(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+
+    for nal in split_annex_b(buf) {
+        let nal_type = nal.first().map_or(0, |b| b & 0x1f);
+        if nal_type == 7 || nal_type == 8 {
+            continue;
+        }
+
+        out.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+
+    out
+}
+
+/// Splits an Annex B buffer into its NAL unit payloads, each with its
+/// start code stripped.
+fn split_annex_b(buf: &[u8]) -> Vec<&[u8]> {
+    let mut markers = Vec::new();
+    let mut i = 0;
+    while i + 3 <= buf.len() {
+        if i + 4 <= buf.len() && buf[i..i + 4] == [0, 0, 0, 1] {
+            markers.push((i, i + 4));
+            i += 4;
+        } else if buf[i..i + 3] == [0, 0, 1] {
+            markers.push((i, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    markers
+        .iter()
+        .enumerate()
+        .map(|(idx, &(_, payload_start))| {
+            let end = markers
+                .get(idx + 1)
+                .map_or(buf.len(), |&(marker_start, _)| marker_start);
+            &buf[payload_start..end]
+        })
+        .collect()
+}
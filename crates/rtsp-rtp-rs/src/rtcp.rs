@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Clock rate assumed for the video RTP timestamp (RFC 3551 names 90kHz as
+/// the standard video clock rate, used regardless of frame rate).
+const CLOCK_RATE_HZ: u32 = 90_000;
+
+const PT_SENDER_REPORT: u8 = 200;
+const PT_RECEIVER_REPORT: u8 = 201;
+
+/// How often to emit a Receiver Report. Several cameras tear a session down
+/// after 30-60s without RTCP feedback, so this comfortably beats that.
+pub(crate) const RR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The NTP/RTP timestamp pair carried by a Sender Report (RFC 3550 6.4.1),
+/// which ties a stream's RTP clock to wall-clock time.
+pub(crate) struct SenderReport {
+    ntp_seconds: u32,
+    ntp_fraction: u32,
+    rtp_timestamp: u32,
+}
+
+/// Parses an RTCP packet as a Sender Report, if that's what it is.
+pub(crate) fn parse_sender_report(packet: &[u8]) -> Option<SenderReport> {
+    if packet.len() < 20 || packet[1] != PT_SENDER_REPORT {
+        return None;
+    }
+
+    Some(SenderReport {
+        ntp_seconds: u32::from_be_bytes(packet[8..12].try_into().ok()?),
+        ntp_fraction: u32::from_be_bytes(packet[12..16].try_into().ok()?),
+        rtp_timestamp: u32::from_be_bytes(packet[16..20].try_into().ok()?),
+    })
+}
+
+/// Tracks what's needed to emit RTCP Receiver Reports and to map RTP
+/// timestamps onto wall-clock time once a Sender Report has arrived.
+pub(crate) struct RtcpState {
+    ssrc: u32,
+    started_at: Instant,
+    last_rr_sent: Option<Instant>,
+    highest_seq: Option<u16>,
+    sender_ssrc: Option<u32>,
+    last_sr: Option<SenderReport>,
+    last_transit: Option<i64>,
+    jitter: f64,
+}
+
+impl RtcpState {
+    pub(crate) fn new() -> Self {
+        RtcpState {
+            ssrc: client_ssrc(),
+            started_at: Instant::now(),
+            last_rr_sent: None,
+            highest_seq: None,
+            sender_ssrc: None,
+            last_sr: None,
+            last_transit: None,
+            jitter: 0.0,
+        }
+    }
+
+    /// Feeds in an arriving RTP packet's header fields, for the reception
+    /// stats a Receiver Report carries (RFC 3550 6.4.1, A.8 for jitter).
+    pub(crate) fn note_rtp_packet(&mut self, sender_ssrc: u32, seq: u16, rtp_timestamp: u32) {
+        self.sender_ssrc = Some(sender_ssrc);
+        if self
+            .highest_seq
+            .map_or(true, |highest| (seq.wrapping_sub(highest) as i16) > 0)
+        {
+            self.highest_seq = Some(seq);
+        }
+
+        let arrival = self.rtp_clock_now();
+        let transit = (arrival as i64).wrapping_sub(rtp_timestamp as i64);
+        if let Some(last_transit) = self.last_transit {
+            let d = (transit - last_transit).unsigned_abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.last_transit = Some(transit);
+    }
+
+    pub(crate) fn note_sender_report(&mut self, sr: SenderReport) {
+        self.last_sr = Some(sr);
+    }
+
+    fn rtp_clock_now(&self) -> u32 {
+        (self.started_at.elapsed().as_secs_f64() * CLOCK_RATE_HZ as f64) as u32
+    }
+
+    /// Whether a Receiver Report is due; marks one as just sent if so.
+    pub(crate) fn rr_due(&mut self) -> bool {
+        let now = Instant::now();
+        let due = match self.last_rr_sent {
+            Some(last) => now.duration_since(last) >= RR_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.last_rr_sent = Some(now);
+        }
+        due
+    }
+
+    /// Builds an RTCP Receiver Report (RFC 3550 6.4.2) with a single
+    /// reception report block for the one sender SSRC this crate ever talks
+    /// to (a camera's video track).
+    pub(crate) fn build_receiver_report(&self) -> Vec<u8> {
+        let report_count: u8 = u8::from(self.sender_ssrc.is_some());
+
+        let mut packet = Vec::with_capacity(8 + 24 * report_count as usize);
+        packet.push(0b1000_0000 | report_count); // V=2, P=0, RC
+        packet.push(PT_RECEIVER_REPORT);
+
+        let length_words: u16 = if report_count == 1 { 7 } else { 1 };
+        packet.extend_from_slice(&length_words.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+
+        if let Some(sender_ssrc) = self.sender_ssrc {
+            packet.extend_from_slice(&sender_ssrc.to_be_bytes());
+            packet.push(0); // fraction lost - not tracked, report none
+            packet.extend_from_slice(&[0, 0, 0]); // cumulative lost - not tracked
+            packet.extend_from_slice(&(self.highest_seq.unwrap_or(0) as u32).to_be_bytes());
+            packet.extend_from_slice(&(self.jitter as u32).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0, 0]); // LSR - not tracked, always zero
+            packet.extend_from_slice(&[0, 0, 0, 0]); // DLSR
+        }
+
+        packet
+    }
+
+    /// Maps an RTP timestamp to wall-clock time using the most recent
+    /// Sender Report's NTP/RTP pair, or `None` before the first SR arrives.
+    pub(crate) fn presentation_time(&self, rtp_timestamp: u32) -> Option<SystemTime> {
+        let sr = self.last_sr.as_ref()?;
+
+        let ntp_unix_secs = sr.ntp_seconds as f64 - 2_208_988_800.0
+            + (sr.ntp_fraction as f64 / u32::MAX as f64);
+
+        let ticks_since_sr = rtp_timestamp.wrapping_sub(sr.rtp_timestamp) as i32;
+        let seconds_since_sr = ticks_since_sr as f64 / CLOCK_RATE_HZ as f64;
+
+        let presentation_secs = ntp_unix_secs + seconds_since_sr;
+        if presentation_secs < 0.0 {
+            return None;
+        }
+
+        Some(UNIX_EPOCH + Duration::from_secs_f64(presentation_secs))
+    }
+}
+
+/// A one-off SSRC to identify ourselves in Receiver Reports. Doesn't need
+/// to be cryptographically random, just distinct enough not to collide
+/// within one session - the current time is good enough for that.
+fn client_ssrc() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0)
+        ^ 0x4e56_5201
+}
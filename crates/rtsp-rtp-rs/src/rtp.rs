@@ -0,0 +1,832 @@
+use crate::recorder::{self, Mp4Recorder};
+use crate::rtcp::{self, RtcpState};
+use anyhow::Result;
+use log::{debug, info, trace, warn};
+use openh264::decoder::{DecodedYUV, Decoder};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+
+pub enum Decoders {
+    OpenH264,
+}
+
+/// Where RTP/RTCP packets actually come from - a dedicated UDP socket, or
+/// demultiplexed off the RTSP TCP connection in interleaved mode.
+enum RtpSource {
+    Udp {
+        socket: UdpSocket,
+        addr_server: SocketAddr,
+        /// RTCP rides the RTP port + 1 (RFC 3550 section 11) - a dedicated
+        /// socket so Sender Reports can be read without blocking the RTP
+        /// recv, and Receiver Reports sent back to the camera.
+        rtcp_socket: UdpSocket,
+    },
+    Interleaved {
+        conn: Arc<Mutex<TcpStream>>,
+        rtp_channel: u8,
+        rtcp_channel: u8,
+    },
+}
+
+pub struct Rtp {
+    source: RtpSource,
+    type_decoder: Option<Decoders>,
+    decoder: Option<Decoder>,
+    reorder: ReorderBuffer,
+    rtcp: RtcpState,
+    buf_rtp: [u8; 2048],
+    buf_temp: Vec<u8>,
+    buf_fragments: Vec<u8>,
+    buf_all: Vec<u8>,
+    /// RTP timestamp of the access unit currently being assembled in
+    /// `buf_temp`, carried over into `last_decoded_rtp_timestamp` once that
+    /// access unit actually decodes.
+    buf_temp_rtp_timestamp: Option<u32>,
+    /// Whether any slice appended into `buf_temp` so far is an IDR, i.e.
+    /// whether the access unit being assembled is a sync sample.
+    buf_temp_is_idr: bool,
+    /// RTP timestamp of the most recently *decoded* frame, used by callers
+    /// to map it to wall-clock presentation time via `presentation_time`.
+    last_decoded_rtp_timestamp: Option<u32>,
+    /// Most recently seen SPS/PPS (Annex B, with start code), cached so an
+    /// IDR can be prefixed with parameter sets even when the camera doesn't
+    /// repeat them before every keyframe.
+    sps_cache: Option<Vec<u8>>,
+    pps_cache: Option<Vec<u8>>,
+    is_start_decoding: bool,
+    is_fragment_start: bool,
+    is_fragment_end: bool,
+    /// The real, unpadded frame size the decoder last reported (see
+    /// `DecodedYUV::dimension_y`), used for the MP4 track header - the
+    /// camera's negotiated resolution, not whatever size the caller
+    /// happens to be displaying frames at.
+    decoded_dimensions: Option<(u16, u16)>,
+    /// Opt-in MP4 archival recording, see `with_recording`.
+    recording: Option<Recording>,
+}
+
+/// Pending MP4 archival recording. The muxer itself (`Mp4Recorder`) isn't
+/// opened until the first access unit actually decodes - that's the
+/// earliest point SPS/PPS and the real frame dimensions are guaranteed
+/// known, both needed to build the `avcC` box.
+struct Recording {
+    path: PathBuf,
+    writer: Option<Mp4Recorder>,
+}
+
+/// How many packets we're willing to hold back waiting for a gap to fill
+/// before giving up on it and catching up to what's actually arrived.
+const REORDER_WINDOW: usize = 128;
+
+/// Puts packets delivered out of order (common over UDP, and still possible
+/// interleaved since TCP only orders bytes within one $-channel) back into
+/// sequence-number order before they reach the depacketizer, which assumes
+/// NAL fragments arrive in the order they were sent.
+///
+/// Sequence numbers are 16-bit and wrap (RFC 3550 5.1), so "next expected"
+/// is tracked modularly: distances between sequence numbers are computed by
+/// wrapping subtraction into an `i16`, which stays correct across the
+/// 65535 -> 0 rollover as long as packets are within `REORDER_WINDOW` of
+/// each other.
+struct ReorderBuffer {
+    next_expected: Option<u16>,
+    buffered: std::collections::HashMap<u16, Vec<u8>>,
+}
+
+impl ReorderBuffer {
+    fn new() -> Self {
+        ReorderBuffer {
+            next_expected: None,
+            buffered: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Buffers `packet` under its sequence number and returns any packets
+    /// that are now ready to be processed, in sequence order.
+    fn push(&mut self, seq: u16, packet: Vec<u8>) -> Vec<Vec<u8>> {
+        let next_expected = *self.next_expected.get_or_insert(seq);
+
+        // Already emitted (sequence number behind `next_expected`, e.g. a
+        // late duplicate): drop it rather than let it sit in `buffered` forever, where it
+        // could later be picked as a high-water skip-ahead target and drive
+        // `next_expected` backward.
+        if seq_diff(seq, next_expected) < 0 {
+            debug!(
+                "[Rtp][reorder] dropping stale/duplicate packet seq {} (next expected {})",
+                seq, next_expected
+            );
+            return Vec::new();
+        }
+
+        self.buffered.insert(seq, packet);
+
+        // High-water mark: we've held back more packets than our window
+        // allows without ever seeing `next_expected` - assume it's gone for
+        // good and jump forward to whatever we've actually got. Only
+        // consider packets at or ahead of `next_expected`; everything else
+        // was already filtered out above.
+        if self.buffered.len() > REORDER_WINDOW {
+            if let Some(&oldest) = self
+                .buffered
+                .keys()
+                .filter(|&&s| seq_diff(s, next_expected) >= 0)
+                .min_by_key(|&&s| seq_diff(s, next_expected))
+            {
+                debug!(
+                    "[Rtp][reorder] high water mark hit, skipping ahead from {} to {}",
+                    next_expected, oldest
+                );
+                self.next_expected = Some(oldest);
+            }
+        }
+
+        let mut ready = Vec::new();
+        while let Some(expected) = self.next_expected {
+            match self.buffered.remove(&expected) {
+                Some(packet) => {
+                    ready.push(packet);
+                    self.next_expected = Some(expected.wrapping_add(1));
+                }
+                None => break,
+            }
+        }
+
+        ready
+    }
+}
+
+/// Signed distance from `b` to `a` on the 16-bit RTP sequence number space,
+/// correct across wraparound as long as the true distance is well under
+/// `i16::MAX`.
+fn seq_diff(a: u16, b: u16) -> i16 {
+    a.wrapping_sub(b) as i16
+}
+
+/// Prepends the 4-byte Annex B start code to a raw NAL unit payload.
+fn annex_b(nal: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + nal.len());
+    buf.extend_from_slice(&[0u8, 0u8, 0u8, 1u8]);
+    buf.extend_from_slice(nal);
+    buf
+}
+
+// ----------------- NOTE
+// Most implementations will break up IDR frames
+// into fragments (e.g. FU-A)
+// see section 5.8 of RFC 6184
+
+// PAYLOAD starts at byte 14
+// which in 0 index array = 13
+// UNLESS this is a fragment (e.g. FU-A)
+// in which case it's byte 15
+// as FU-A has extra byte for header
+
+// Start prefix code (3 or 4 bytes)
+// For beginning of entire stream or SPS/PPS nal units -> 0x00 0x00 x00 0x01
+// All other nal units use -> 0x00 0x00 0x01
+
+// Byte index where NAL unit starts in RTP packet
+// This is also where the NAL header is which is 1 byte
+const NAL_UNIT_START: usize = 12;
+
+impl Rtp {
+    pub async fn new(
+        client_ip: Option<&str>,
+        client_port: u16,
+        addr_server: SocketAddr,
+    ) -> Result<Self> {
+        // Allow manual selection of client IP which is IP that RTP/UDP server socket will listen
+        // otherwise use default of 0.0.0.0
+        // client PORT is chosen normally before RTSP comm and sent to server during 'SETUP' command
+        // server responds with it's server PORT to send RTP
+        let addr_client = match client_ip {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip.parse()?), client_port),
+            None => format!("0.0.0.0:{client_port}").parse()?,
+        };
+
+        let socket = UdpSocket::bind(addr_client).await?;
+
+        // RTCP companion channel: same IP, next port up (RFC 3550 11).
+        let addr_client_rtcp = SocketAddr::new(addr_client.ip(), addr_client.port() + 1);
+        let rtcp_socket = UdpSocket::bind(addr_client_rtcp).await?;
+
+        let result = Rtp {
+            source: RtpSource::Udp {
+                socket,
+                addr_server,
+                rtcp_socket,
+            },
+            type_decoder: None,
+            decoder: None,
+            reorder: ReorderBuffer::new(),
+            rtcp: RtcpState::new(),
+            buf_rtp: [0u8; 2048],
+            buf_temp: Vec::new(),
+            buf_fragments: Vec::new(),
+            buf_all: Vec::new(),
+            buf_temp_rtp_timestamp: None,
+            buf_temp_is_idr: false,
+            last_decoded_rtp_timestamp: None,
+            sps_cache: None,
+            pps_cache: None,
+            is_start_decoding: false,
+            is_fragment_start: false,
+            is_fragment_end: false,
+            decoded_dimensions: None,
+            recording: None,
+        };
+
+        Ok(result)
+    }
+
+    /// Construct an `Rtp` that reads `$`-framed RTP/RTCP off the RTSP TCP
+    /// connection rather than binding its own UDP socket, for cameras SETUP
+    /// negotiated as `RTP/AVP/TCP;interleaved=...` with.
+    pub async fn new_interleaved(
+        conn: Arc<Mutex<TcpStream>>,
+        rtp_channel: u8,
+        rtcp_channel: u8,
+    ) -> Result<Self> {
+        Ok(Rtp {
+            source: RtpSource::Interleaved {
+                conn,
+                rtp_channel,
+                rtcp_channel,
+            },
+            type_decoder: None,
+            decoder: None,
+            reorder: ReorderBuffer::new(),
+            rtcp: RtcpState::new(),
+            buf_rtp: [0u8; 2048],
+            buf_temp: Vec::new(),
+            buf_fragments: Vec::new(),
+            buf_all: Vec::new(),
+            buf_temp_rtp_timestamp: None,
+            buf_temp_is_idr: false,
+            last_decoded_rtp_timestamp: None,
+            sps_cache: None,
+            pps_cache: None,
+            is_start_decoding: false,
+            is_fragment_start: false,
+            is_fragment_end: false,
+            decoded_dimensions: None,
+            recording: None,
+        })
+    }
+
+    /// Seeds the SPS/PPS cache from DESCRIBE's SDP `sprop-parameter-sets`
+    /// (see `Rtsp::sprop_parameter_sets`), for cameras that never send
+    /// parameter sets in-band over RTP. Harmless to call even when the
+    /// camera does send them in-band - whichever arrives will simply
+    /// overwrite this cache.
+    pub fn prime_parameter_sets(&mut self, sps: &[u8], pps: &[u8]) {
+        self.sps_cache = Some(annex_b(sps));
+        self.pps_cache = Some(annex_b(pps));
+    }
+
+    /// Opts into archiving the decoded stream to an MP4 file at `path`
+    /// alongside whatever else the caller does with each decoded frame
+    /// (e.g. live SDL2 display). `path` only appears once `finish_recording`
+    /// runs - see `Mp4Recorder` for why - so don't expect it to exist, let
+    /// alone be playable, while the session is still running.
+    ///
+    /// The MP4 file itself isn't opened until the first access unit
+    /// decodes, since that's the earliest point SPS/PPS are guaranteed
+    /// cached and the real frame dimensions (`DecodedYUV::dimension_y`)
+    /// are known for the track header; nothing is written if the stream
+    /// never decodes a frame.
+    pub fn with_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.recording = Some(Recording {
+            path: path.into(),
+            writer: None,
+        });
+        self
+    }
+
+    /// Flushes and finalizes the MP4 recording, if one was started with
+    /// `with_recording`. Safe to call even if recording was never enabled,
+    /// or if no frame ever decoded.
+    pub fn finish_recording(&mut self) -> Result<()> {
+        if let Some(recording) = self.recording.take() {
+            if let Some(writer) = recording.writer {
+                writer.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn connect(&mut self, decoder: Decoders) -> Result<()> {
+        match decoder {
+            Decoders::OpenH264 => {
+                let openh264_decoder = Decoder::new()?;
+                self.decoder = Some(openh264_decoder);
+            }
+        }
+
+        self.type_decoder = Some(decoder);
+
+        // Connect to the RTP camera server using IP and port provided in
+        // SETUP response. In the RTP specs, the RTCP server should be port
+        // 6601 and will always need to be a different port. Interleaved
+        // mode has nothing to connect - RTP/RTCP already ride the RTSP
+        // connection that's already open.
+        if let RtpSource::Udp {
+            socket,
+            addr_server,
+            rtcp_socket,
+        } = &self.source
+        {
+            socket.connect(*addr_server).await?;
+
+            let addr_server_rtcp = SocketAddr::new(addr_server.ip(), addr_server.port() + 1);
+            rtcp_socket.connect(addr_server_rtcp).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn save_file(&self) {
+        let path = Path::new("video.h264");
+        let display = path.display();
+
+        // Open a file in write-only mode, returns `io::Result<File>`
+        let mut file = match File::create(&path).await {
+            Err(why) => panic!("couldn't create {}: {}", display, why),
+            Ok(file) => file,
+        };
+
+        match file.write_all(&self.buf_all).await {
+            Err(why) => panic!("couldn't write to {}: {}", display, why),
+            Ok(_) => info!("successfully wrote to {}", display),
+        }
+    }
+
+    pub async fn get_rtp(&mut self) -> Result<()> {
+        let raw_packet: Vec<u8> = match &mut self.source {
+            RtpSource::Udp {
+                socket,
+                rtcp_socket,
+                ..
+            } => {
+                // Sender Reports arrive unsolicited on their own socket;
+                // drain whatever's pending without blocking the RTP read.
+                let mut rtcp_buf = [0u8; 1500];
+                loop {
+                    match rtcp_socket.try_recv(&mut rtcp_buf) {
+                        Ok(len) => {
+                            if let Some(sr) = rtcp::parse_sender_report(&rtcp_buf[..len]) {
+                                self.rtcp.note_sender_report(sr);
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            trace!("[Rtp][get_rtp] error polling RTCP socket: {e}");
+                            break;
+                        }
+                    }
+                }
+
+                let len = socket.recv(&mut self.buf_rtp).await?;
+                self.buf_rtp[..len].to_vec()
+            }
+            RtpSource::Interleaved { conn, rtp_channel, rtcp_channel } => {
+                let rtp_channel = *rtp_channel;
+                let rtcp_channel = *rtcp_channel;
+
+                // RTCP frames ride the same connection on their own channel;
+                // keep reading `$`-framed packets until we get one that's
+                // actually RTP, feeding any Sender Reports we pass along
+                // the way into `self.rtcp`.
+                loop {
+                    let mut stream = conn.lock().await;
+
+                    let mut header = [0u8; 4];
+                    stream.read_exact(&mut header).await?;
+
+                    if header[0] != 0x24 {
+                        return Err(anyhow::anyhow!(
+                            "[Rtp][get_rtp] expected '$' interleaved frame marker, got {:#x}",
+                            header[0]
+                        ));
+                    }
+
+                    let channel = header[1];
+                    let payload_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+                    let mut payload = vec![0u8; payload_len];
+                    stream.read_exact(&mut payload).await?;
+                    drop(stream);
+
+                    if channel == rtp_channel {
+                        break payload;
+                    } else if channel == rtcp_channel {
+                        trace!(
+                            "[Rtp][get_rtp] got {payload_len}-byte RTCP frame on channel {channel}"
+                        );
+                        if let Some(sr) = rtcp::parse_sender_report(&payload) {
+                            self.rtcp.note_sender_report(sr);
+                        }
+                    } else {
+                        trace!(
+                            "[Rtp][get_rtp] discarding {payload_len}-byte frame on unexpected channel {channel}"
+                        );
+                    }
+                }
+            }
+        };
+
+        // Need at least the 12-byte fixed RTP header (RFC 3550) plus a NAL
+        // header byte to do anything useful with this packet.
+        if raw_packet.len() <= NAL_UNIT_START {
+            trace!(
+                "[Rtp][get_rtp] dropping short packet ({} bytes)",
+                raw_packet.len()
+            );
+            return Ok(());
+        }
+
+        // Sequence number is bytes 2-3, RTP timestamp bytes 4-7, and sender
+        // SSRC bytes 8-11 of the RTP header (RFC 3550 5.1) - sequence number
+        // reorders packets before depacketizing, the rest feed the RTCP
+        // Receiver Report and presentation-time mapping.
+        let seq = u16::from_be_bytes([raw_packet[2], raw_packet[3]]);
+        let rtp_timestamp = u32::from_be_bytes(raw_packet[4..8].try_into().unwrap());
+        let sender_ssrc = u32::from_be_bytes(raw_packet[8..12].try_into().unwrap());
+
+        self.rtcp.note_rtp_packet(sender_ssrc, seq, rtp_timestamp);
+        if self.rtcp.rr_due() {
+            self.send_receiver_report().await?;
+        }
+
+        for in_order_packet in self.reorder.push(seq, raw_packet) {
+            self.process_packet(&in_order_packet);
+        }
+
+        Ok(())
+    }
+
+    /// Sends an RTCP Receiver Report back to the camera, over whichever
+    /// transport RTP itself is riding.
+    async fn send_receiver_report(&mut self) -> Result<()> {
+        let report = self.rtcp.build_receiver_report();
+
+        match &self.source {
+            RtpSource::Udp { rtcp_socket, .. } => {
+                rtcp_socket.send(&report).await?;
+            }
+            RtpSource::Interleaved {
+                conn, rtcp_channel, ..
+            } => {
+                let mut framed = Vec::with_capacity(4 + report.len());
+                framed.push(0x24);
+                framed.push(*rtcp_channel);
+                framed.extend_from_slice(&(report.len() as u16).to_be_bytes());
+                framed.extend_from_slice(&report);
+
+                let mut stream = conn.lock().await;
+                stream.write_all(&framed).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_packet(&mut self, packet: &[u8]) {
+        let len = packet.len();
+
+        // RTP timestamp (bytes 4-7) is shared by every packet of the same
+        // access unit (RFC 3550 5.1), so this just needs to be captured
+        // once per frame - repeatedly overwriting it with the same value
+        // from later packets in the frame is harmless.
+        self.buf_temp_rtp_timestamp = Some(u32::from_be_bytes(
+            packet[4..8].try_into().expect("RTP header is >= 12 bytes"),
+        ));
+
+        // Get first 16 BITS of RTP packet which is part of header (RFC 6184)
+        let rtp_header_pt1 = &packet[0];
+        let rtp_header_pt2 = &packet[1];
+        trace!(
+            "RTP Header ------->>> {:08b}{:08b}",
+            rtp_header_pt1,
+            rtp_header_pt2
+        );
+
+        // NAL Unit Header (1st byte of NAL unit)
+        // +---------------+
+        // |0|1|2|3|4|5|6|7|
+        // +-+-+-+-+-+-+-+-+
+        // |F|NRI|  Type   |
+        // +---------------+
+
+        // BYTE 12 is NAL unit header (because of 0 index)
+        let nal_header = &packet[NAL_UNIT_START];
+
+        // Get the NAL unit header TYPE (last 5 BITS)
+        // Use mask 00011111 = decimal 31
+        let nal_header_type = nal_header & 31;
+
+        trace!("{} bytes received", len);
+        trace!("-----------\n{:08b}", nal_header);
+        trace!(
+            "NAL HEADER TYPE: ---------->>> {}:{}",
+            nal_header_type,
+            get_nal_type(nal_header_type)
+        );
+
+        trace!("NAL HEADER ---->> {:08b}", nal_header);
+
+        // Check if this is an SPS packet
+        // NAL header byte -> 01100111
+        if nal_header_type == 7u8 {
+            trace!("Sequence started! --------------------------------------");
+
+            self.sps_cache = Some(annex_b(&packet[NAL_UNIT_START..len]));
+        }
+        // Check if this is an PPS packet
+        else if nal_header_type == 8u8 {
+            debug!("PPS packet ----- ");
+
+            self.pps_cache = Some(annex_b(&packet[NAL_UNIT_START..len]));
+        }
+        // Check if this is an SEI packet
+        else if nal_header_type == 6u8 {
+            debug!("SEI packet ----- ");
+
+            if self.is_start_decoding {
+                self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
+                self.buf_temp
+                    .extend_from_slice(&packet[NAL_UNIT_START..len]);
+            }
+        }
+        // Check for fragment (FU-A)
+        else if nal_header_type == 28u8 {
+            debug!("Fragment started!! ----- ");
+            self.is_fragment_start = true;
+
+            // Fragment header (2nd NAL unit byte)
+            //  +---------------+
+            // |0|1|2|3|4|5|6|7| bit position
+            // +-+-+-+-+-+-+-+-+
+            // |S|E|R|  Type   |
+            // +---------------+
+            // S = Start of fragment?
+            // E = End of fragment?
+
+            // Check fragment header which is byte
+            // after NAL header
+            let header_frag = &packet[13];
+            debug!("Fragment header -- {:08b}", header_frag);
+
+            // Original NAL unit type this fragment carries (last 5 bits),
+            // used below to decide if this is the IDR we're gating on.
+            let fragment_nal_type = *header_frag & 0b00011111;
+
+            // Or fragment END?
+            if *header_frag & 0b01000000 == 64u8 {
+                trace!("Fragment ended!! ----- ");
+                self.is_fragment_end = true;
+
+                // Reconstruct new NAL header using NAL
+                // NAL unit type in FRAGMENT header
+                // AND NAL priority from original NAL header
+                // use bitmasks to get first 3 bits and last 5 bits
+                let nal_header = fragment_nal_type | (packet[NAL_UNIT_START] & 0b11100000);
+                debug!("New NAL header for conbined fragment: {:08b}", nal_header);
+
+                let is_idr = fragment_nal_type == 5u8;
+                if self.stage_slice(is_idr) {
+                    self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
+                    // Need to swap outside nal header to inside payload type
+                    // as after combining packet it's not a fragment anymore
+                    self.buf_temp.push(nal_header);
+                    self.buf_temp
+                        .extend_from_slice(self.buf_fragments.as_slice());
+                    self.buf_temp.extend_from_slice(&packet[14..len]);
+                    self.buf_temp_is_idr |= is_idr;
+                }
+                self.buf_fragments.clear();
+            } else {
+                // Append fragment payload EXCLUDING ALL HEADERS
+                self.buf_fragments.extend_from_slice(&packet[14..len]);
+            }
+        } else {
+            debug!("Slice packet ----- ");
+
+            let is_idr = nal_header_type == 5u8;
+            if self.stage_slice(is_idr) {
+                self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
+                self.buf_temp
+                    .extend_from_slice(&packet[NAL_UNIT_START..len]);
+                self.buf_temp_is_idr |= is_idr;
+            }
+        }
+    }
+
+    /// Gates decoding on having seen SPS+PPS followed by an IDR. Until
+    /// `is_start_decoding` is set, only an IDR slice is let through - and
+    /// only once both parameter sets are cached, in which case they're
+    /// prefixed onto `buf_temp` right here. Returns whether the caller
+    /// should go on to append this NAL's own bytes, or drop it as a
+    /// startup garbage frame.
+    fn stage_slice(&mut self, is_idr: bool) -> bool {
+        if self.is_start_decoding {
+            return true;
+        }
+
+        if !is_idr {
+            // Still waiting for our first IDR - can't decode a lone
+            // non-IDR slice without a reference frame anyway.
+            return false;
+        }
+
+        match (&self.sps_cache, &self.pps_cache) {
+            (Some(sps), Some(pps)) => {
+                self.buf_temp.extend_from_slice(sps);
+                self.buf_temp.extend_from_slice(pps);
+                self.is_start_decoding = true;
+                true
+            }
+            // Got an IDR before we have both parameter sets cached - still
+            // garbage, keep waiting.
+            _ => false,
+        }
+    }
+
+    pub fn try_decode(&mut self) -> Result<Option<DecodedYUV<'_>>, openh264::Error> {
+        if self.buf_temp.is_empty() || !self.is_start_decoding {
+            return Ok(None);
+        }
+        if self.is_fragment_start && !self.is_fragment_end {
+            return Ok(None);
+        }
+
+        // Clear fragment flags
+        self.is_fragment_start = false;
+        self.is_fragment_end = false;
+
+        // all current packets data
+        self.buf_all.extend_from_slice(self.buf_temp.as_slice());
+
+        // DECODE
+        // Idea is to store all packets depending on types in buf_temp
+        // SPS/PPS     = 2 packets
+        // Fragment    = 1 packet COMBINED
+        // Slice       = 1 packet
+        debug!("//////////////////////////////////////////");
+        debug!("Decoding packet size: {:?}", self.buf_temp.len());
+
+        let rtp_timestamp = self.buf_temp_rtp_timestamp.take();
+        let is_idr = std::mem::take(&mut self.buf_temp_is_idr);
+
+        let maybe_some_yuv = match &mut self.decoder {
+            Some(rtp_decoder) => rtp_decoder.decode(self.buf_temp.as_slice()),
+            None => Err(openh264::Error::msg("Unable to decode NAL unit")),
+        };
+
+        // Learn the real, unpadded frame size from whatever just decoded -
+        // the camera's negotiated resolution, not a caller-guessed size -
+        // for the MP4 track header `record_sample` builds below.
+        if let Ok(Some(yuv)) = &maybe_some_yuv {
+            let (width, height) = yuv.dimension_y();
+            self.decoded_dimensions = Some((width as u16, height as u16));
+        }
+
+        // Archive this access unit regardless of whether openh264 above
+        // managed to decode it - recording just mirrors what the camera
+        // sent - except the very first sample, which needs a successful
+        // decode at least once to learn the frame dimensions above.
+        // `record_sample` takes its fields individually rather than
+        // `&mut self` since `maybe_some_yuv` still holds `self.decoder`
+        // borrowed for the return value at this point.
+        if let Some(rtp_timestamp) = rtp_timestamp {
+            if let Err(e) = Self::record_sample(
+                &mut self.recording,
+                &self.buf_temp,
+                &self.sps_cache,
+                &self.pps_cache,
+                self.decoded_dimensions,
+                rtp_timestamp,
+                is_idr,
+            ) {
+                warn!("[Rtp][recording] failed to write MP4 sample: {e}");
+            }
+        }
+
+        if matches!(maybe_some_yuv, Ok(Some(_))) {
+            self.last_decoded_rtp_timestamp = rtp_timestamp;
+        }
+
+        self.buf_temp.clear();
+
+        maybe_some_yuv
+    }
+
+    /// Writes the access unit currently in `buf_temp` to the MP4 recording,
+    /// if one is enabled - opening the file on the first call, since that's
+    /// the earliest point SPS/PPS and the real frame `dimensions` are
+    /// guaranteed known.
+    fn record_sample(
+        recording: &mut Option<Recording>,
+        buf_temp: &[u8],
+        sps_cache: &Option<Vec<u8>>,
+        pps_cache: &Option<Vec<u8>>,
+        dimensions: Option<(u16, u16)>,
+        rtp_timestamp: u32,
+        is_idr: bool,
+    ) -> Result<()> {
+        let Some(recording) = recording else {
+            return Ok(());
+        };
+
+        if recording.writer.is_none() {
+            let (Some(sps), Some(pps), Some((width, height))) = (sps_cache, pps_cache, dimensions)
+            else {
+                // Nothing to mux yet - wait for parameter sets to be
+                // cached and a frame to actually decode once, so the
+                // track header can be built with the real resolution.
+                return Ok(());
+            };
+
+            // Strip the Annex B start code `annex_b` prepended - the
+            // `avcC` box wants the raw NAL payload.
+            recording.writer = Some(Mp4Recorder::create(
+                &recording.path,
+                width,
+                height,
+                &sps[4..],
+                &pps[4..],
+            )?);
+        }
+
+        let nal_units = recorder::annex_b_to_avcc(buf_temp);
+        recording
+            .writer
+            .as_mut()
+            .expect("just created above if absent")
+            .write_sample(rtp_timestamp, is_idr, nal_units)
+    }
+
+    /// RTP timestamp of the most recently decoded frame, for mapping to
+    /// wall-clock time via `presentation_time`.
+    pub fn last_rtp_timestamp(&self) -> Option<u32> {
+        self.last_decoded_rtp_timestamp
+    }
+
+    /// Maps an RTP timestamp (see `last_rtp_timestamp`) to the wall-clock
+    /// time the camera intended it to be presented at, once an RTCP Sender
+    /// Report has arrived to anchor the RTP clock to NTP time. `None` before
+    /// the first Sender Report.
+    pub fn presentation_time(&self, rtp_timestamp: u32) -> Option<SystemTime> {
+        self.rtcp.presentation_time(rtp_timestamp)
+    }
+}
+
+fn get_nal_type(nal: u8) -> String {
+    let nal_types = r#"0:Unspecified:non-VCL
+        1:Coded slice of a non-IDR picture slice_layer_without_partitioning_rbsp():VCL
+        2:Coded slice data partition A slice_data_partition_a_layer_rbsp():VCL
+        3:Coded slice data partition B slice_data_partition_b_layer_rbsp():VCL
+        4:Coded slice data partition C slice_data_partition_c_layer_rbsp():VCL
+        5:Coded slice of an IDR picture slice_layer_without_partitioning_rbsp():VCL
+        6:Supplemental enhancement information (SEI) sei_rbsp():non-VCL
+        7:Sequence parameter set seq_parameter_set_rbsp():non-VCL
+        8:Picture parameter set pic_parameter_set_rbsp():non-VCL
+        9:Access unit delimiter access_unit_delimiter_rbsp():non-VCL
+        10:End of sequence end_of_seq_rbsp():non-VCL
+        11:End of stream end_of_stream_rbsp():non-VCL
+        12:Filler data filler_data_rbsp():non-VCL
+        13:Sequence parameter set extension seq_parameter_set_extension_rbsp():non-VCL
+        14:Prefix NAL unit prefix_nal_unit_rbsp():non-VCL
+        15:Subset sequence parameter set subset_seq_parameter_set_rbsp():non-VCL
+        16:Reserved:non-VCL
+        17:Reserved:non-VCL
+        18:Reserved:non-VCL
+        19:Coded slice of an auxiliary coded picture without partitioning slice_layer_without_partitioning_rbsp():non-VCL
+        20:Coded slice extension slice_layer_extension_rbsp():non-VCL
+        21:Coded slice extension for depth view components slice_layer_extension_rbsp() (specified in Annex I):non-VCL
+        22:Reserved:non-VCL
+        23:Reserved:non-VCL
+        24:STAP-A:non-VCL
+        25:STAP-B:non-VCL
+        26:MTAP16:non-VCL
+        27:MTAP24:non-VCL
+        28:FU-A:non-VCL
+        29:FU-B:non-VCL
+        30:reserved:non-VCL
+        31:reserved:non-VCL"#;
+
+    nal_types
+        .lines()
+        .enumerate()
+        .filter(|(i, _)| *i as u8 == nal)
+        .map(|(_, line)| line.split(':').collect::<Vec<&str>>()[1])
+        .collect::<String>()
+}
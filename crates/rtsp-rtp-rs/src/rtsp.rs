@@ -0,0 +1,418 @@
+use anyhow::Result;
+use url::Url;
+use tokio::net::TcpStream;
+use tokio::io::{AsyncWriteExt, ErrorKind};
+use tokio::sync::Mutex;
+use log::debug;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub enum Methods {
+    Options,
+    Describe,
+    Setup,
+    Play,
+    Teardown,
+}
+
+/// How RTP/RTCP travels between camera and client.
+///
+/// `Udp` is the classic two-port pair negotiated via `client_port=` in
+/// SETUP. `Interleaved` multiplexes `$`-framed RTP/RTCP onto the same TCP
+/// connection as the RTSP control messages (`interleaved=0-1` in SETUP),
+/// which is the only option a lot of NAT'd/firewalled cameras allow.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Interleaved,
+}
+
+/// RTP/RTCP channel numbers negotiated for interleaved mode, by convention
+/// RTP on the even channel and RTCP on the next odd one.
+pub const INTERLEAVED_CHANNEL_RTP: u8 = 0;
+pub const INTERLEAVED_CHANNEL_RTCP: u8 = 1;
+
+/// Username/password for cameras that challenge DESCRIBE/SETUP with a 401.
+/// Basic and Digest are both supported; only the scheme named by the
+/// camera's `WWW-Authenticate` challenge is used.
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+pub struct Rtsp {
+    pub response_ok: bool,
+    pub server_addr_rtp: Option<SocketAddr>,
+    pub client_port_rtp: u16, // our port which server will send RTP
+    /// SPS/PPS (Annex B payload, no start code) decoded from DESCRIBE's SDP
+    /// `fmtp` `sprop-parameter-sets`, for cameras that don't send parameter
+    /// sets in-band over RTP.
+    pub sprop_parameter_sets: Option<(Vec<u8>, Vec<u8>)>,
+    server_addr_rtsp: SocketAddr,
+    response_txt: String,
+    cseq: u32,
+    tcp_addr: SocketAddr,
+    stream: Arc<Mutex<TcpStream>>,
+    transport: String,
+    transport_mode: Transport,
+    track: String,
+    id: String,
+    credentials: Option<Credentials>,
+    authorization: Option<String>,
+}
+
+impl Rtsp {
+    pub async fn new(
+        addr: &str,
+        port_rtp: Option<u16>,
+        transport_mode: Transport,
+        credentials: Option<Credentials>,
+    ) -> Result<Self> {
+        let client_port_rtp = port_rtp.unwrap_or(4588u16); // choose a sensible default
+
+        let socket_addr = match Url::parse(addr) {
+            Ok(parsed_addr) => parsed_addr.socket_addrs(|| None)?,
+            Err(e) => panic!("[Rtsp] Trying to parse {addr} resulted in {e}"),
+        };
+
+        let tcp_stream = TcpStream::connect(socket_addr[0]).await?;
+
+        println!("[Rtsp] Connecting to server at: {}", socket_addr[0]);
+
+        Ok(Rtsp {
+            response_ok: false,
+            server_addr_rtp: None,
+            sprop_parameter_sets: None,
+            server_addr_rtsp: socket_addr[0],
+            client_port_rtp,
+            response_txt: String::new(),
+            tcp_addr: socket_addr[0],
+            stream: Arc::new(Mutex::new(tcp_stream)),
+            transport: String::new(),
+            transport_mode,
+            track: String::new(),
+            id: String::new(),
+            credentials,
+            authorization: None,
+            cseq: 1,
+        })
+    }
+
+    /// A shared handle to the RTSP TCP connection, for `Rtp::new_interleaved`
+    /// to read `$`-framed RTP/RTCP packets off of. `Rtsp` keeps its own
+    /// handle too, so it can still send e.g. `Teardown` afterwards.
+    pub fn interleaved_handle(&self) -> Arc<Mutex<TcpStream>> {
+        self.stream.clone()
+    }
+
+    #[rustfmt::skip]
+    pub async fn send(&mut self, method_in: Methods) -> Result<&mut Self> {
+        let method_str = match method_in {
+            Methods::Options     => "OPTIONS",
+            Methods::Describe    => "DESCRIBE",
+            Methods::Setup       => "SETUP",
+            Methods::Play        => "PLAY",
+            Methods::Teardown    => "TEARDOWN",
+        };
+
+        // I think you need to append the token received in SETUP
+        // response here? With my test camera, it wasn't needed
+
+        // Add headers to request for different methods
+        match method_in {
+            Methods::Options     => {
+                println!("[Rtsp][send] Message::Options sending...");    
+            }
+            Methods::Describe    => {
+                println!("[Rtsp][send] Message::Describe sending...");    
+            }
+            Methods::Setup       => {
+                println!("[Rtsp][send] Message::Setup sending...");
+                let uni_multicast = "unicast";
+
+                self.transport = match self.transport_mode {
+                    Transport::Udp => {
+                        // Client port is port you are telling server that it needs to send RTP
+                        // traffic to. Add +1 to selected port for RTCP traffic. This is by
+                        // convention and recommended in RFC.
+                        let client_port = format!("{}-{}", self.client_port_rtp, self.client_port_rtp + 1);
+                        format!("Transport: RTP/AVP/UDP;{uni_multicast};client_port={client_port}\r\n")
+                    }
+                    Transport::Interleaved => {
+                        format!(
+                            "Transport: RTP/AVP/TCP;{uni_multicast};interleaved={}-{}\r\n",
+                            INTERLEAVED_CHANNEL_RTP, INTERLEAVED_CHANNEL_RTCP,
+                        )
+                    }
+                };
+                self.track = "/trackID=0\r\n".to_string();
+            }
+            Methods::Play        => {
+                println!("[Rtsp][send] Message::Play sending...");    
+                self.transport = String::new();
+                self.track = String::new();
+            }
+            Methods::Teardown    => {
+                println!("[Rtsp][send] Message::Teardown sending...");    
+            }
+        }
+
+        // Send command with proper headers
+        // every command must provide cseq
+        // which is incremented sequence as a header
+        let request = self.build_request(method_str);
+        let response = Self::write_and_read(&self.stream, &request).await?;
+        self.cseq += 1;
+        self.check_ok(&response, method_str);
+
+        // Camera challenged us for credentials - compute the Authorization
+        // header for whatever scheme it asked for and resend the same
+        // request once with it attached.
+        if !self.response_ok {
+            if let Some(auth) = self.authenticate(method_str) {
+                self.authorization = Some(auth);
+
+                let request = self.build_request(method_str);
+                let response = Self::write_and_read(&self.stream, &request).await?;
+                self.cseq += 1;
+                self.check_ok(&response, method_str);
+            }
+        }
+
+        match method_in {
+            Methods::Options     => (),
+            Methods::Describe    => self.parse_describe(),
+            Methods::Setup       => self.parse_setup(),
+            Methods::Play        => (),
+            Methods::Teardown    => self.parse_stop(),
+        }
+
+        Ok(self)
+    }
+
+    fn build_request(&self, method_str: &str) -> String {
+        let auth_line = match &self.authorization {
+            Some(value) => format!("Authorization: {value}\r\n"),
+            None => String::new(),
+        };
+
+        format!(
+            "{} {}{} RTSP/1.0\r\nCSeq: {}\r\n{}{}{}\r\n",
+            method_str,
+            self.tcp_addr,
+            self.track,
+            self.cseq,
+            self.transport,
+            auth_line,
+            self.id,
+        )
+    }
+
+    /// The request-target as used in the request line, with the stray CRLF
+    /// that `self.track` carries trimmed off - needed clean for the Digest
+    /// `uri` parameter.
+    fn request_uri(&self) -> String {
+        format!("{}{}", self.tcp_addr, self.track.trim())
+    }
+
+    async fn write_and_read(stream: &Mutex<TcpStream>, request: &str) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(4096);
+
+        let mut stream = stream.lock().await;
+        stream.write_all(request.as_bytes()).await?;
+
+        loop {
+            // Wait for the socket to be readable
+            stream.readable().await?;
+
+            // Try to read data, this may still fail with `WouldBlock`
+            // if the readiness event is a false positive.
+            match stream.try_read_buf(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    /// Parses a `401` response's `WWW-Authenticate` challenge and builds the
+    /// matching `Authorization` header value (without the header name), or
+    /// `None` if we have no credentials, weren't challenged, or the scheme
+    /// isn't one we support.
+    fn authenticate(&self, method_str: &str) -> Option<String> {
+        let creds = self.credentials.as_ref()?;
+
+        if !self.response_txt.contains(" 401 ") {
+            return None;
+        }
+
+        let challenge = self
+            .response_txt
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("www-authenticate:"))?
+            .split_once(':')?
+            .1
+            .trim();
+
+        let uri = self.request_uri();
+
+        if let Some(rest) = challenge.strip_prefix("Digest ") {
+            let params = parse_auth_params(rest);
+            let realm = params.get("realm")?;
+            let nonce = params.get("nonce")?;
+
+            // HA1 = MD5(username:realm:password)
+            // HA2 = MD5(method:uri)
+            // response = MD5(HA1:nonce:HA2)
+            let ha1 = format!(
+                "{:x}",
+                md5::compute(format!("{}:{realm}:{}", creds.username, creds.password))
+            );
+            let ha2 = format!("{:x}", md5::compute(format!("{method_str}:{uri}")));
+            let response = format!("{:x}", md5::compute(format!("{ha1}:{nonce}:{ha2}")));
+
+            Some(format!(
+                "Digest username=\"{}\", realm=\"{realm}\", nonce=\"{nonce}\", uri=\"{uri}\", response=\"{response}\"",
+                creds.username,
+            ))
+        } else if challenge.starts_with("Basic ") {
+            let token = BASE64.encode(format!("{}:{}", creds.username, creds.password));
+            Some(format!("Basic {token}"))
+        } else {
+            None
+        }
+    }
+
+    fn check_ok(&mut self, response: &[u8], method: &str) {
+        let response = String::from_utf8_lossy(response).to_string();
+
+        if response.is_empty() {
+            eprintln!("[Rtsp][send] {method} Response is empty.");
+        }
+        else {
+            debug!("//--------------------- {method} RESPONSE");
+            debug!("{:#?}", &response);
+        }
+
+        self.response_ok = response.contains("200 OK");
+        self.response_txt = response;
+    }
+
+    // Parse OPTIONS methods to determine available methods/commands
+    // fn parse_options(&mut self) {}
+    // fn parse_play(&mut self) {}
+
+    fn parse_describe(&mut self) {
+        // SDP data begins after \r\n\r\n
+        let (_headers, sdp) = self.response_txt.split_once("\r\n\r\n").unwrap();
+
+        debug!("SDP ///---------------\n{:?}", sdp.lines());
+
+        // Some cameras (e.g. Axis with "PS Enabled" off) never send SPS/PPS
+        // in-band over RTP; DESCRIBE's SDP is the only place they show up,
+        // base64 encoded in the fmtp `sprop-parameter-sets` attribute as
+        // `<sps>,<pps>`.
+        self.sprop_parameter_sets = sdp
+            .lines()
+            .find_map(|line| line.split_once("sprop-parameter-sets="))
+            .and_then(|(_, rest)| {
+                let value = rest.split(';').next().unwrap_or(rest).trim();
+                let (sps_b64, pps_b64) = value.split_once(',')?;
+                let sps = BASE64.decode(sps_b64).ok()?;
+                let pps = BASE64.decode(pps_b64).ok()?;
+                Some((sps, pps))
+            });
+    }
+
+    fn parse_setup(&mut self) {
+        let resp_headers = self.response_txt.lines();
+
+        // Parse response from SETUP command
+        let setup_hash: HashMap<&str, &str> = resp_headers
+            .into_iter()
+            .filter(|line| line.contains(":"))
+            .map(|line| line.split(": ").collect::<Vec<&str>>())
+            .map(|v| (v[0], v[1]))
+            .collect();
+
+        // Parse the Transport header of the response
+        // which contains:
+        // 'server_port'
+        // 'ssrc'
+        // 'source' => server IP
+        let transport_hash: HashMap<&str, &str> = setup_hash
+            .get("Transport")
+            .unwrap()
+            .split(';')
+            .collect::<Vec<&str>>()
+            .iter()
+            .filter(|s| s.contains('='))
+            .map(|line| line.split('=').collect::<Vec<&str>>())
+            .map(|v| (v[0], v[1]))
+            .collect();
+
+        // In interleaved mode RTP/RTCP ride the existing RTSP connection, so
+        // there's no separate server RTP address/port to discover here -
+        // just require an `interleaved` parameter in the response and log
+        // it. The channels it names are not compared with the ones we
+        // asked for.
+        if self.transport_mode == Transport::Interleaved {
+            let interleaved = transport_hash
+                .get("interleaved")
+                .expect("[RTSP][parse_setup] Error finding interleaved channels in response");
+            debug!("[RTSP][parse_setup] Interleaved channels: {interleaved}");
+        } else {
+            // Create a new server socket address to talk to it via RTP
+            // The address will have the same IP, but the port is sent
+            // via the 'SETUP' command
+            let server_port = transport_hash.get("server_port")
+                .expect("[RTSP][parse_setup] Error finding server_port in response");
+
+            // server_port returns port range (e.g. 6600-6601)
+            // first port is RTP port
+            // second port is RTCP port
+            let server_rtp_rtcp: Vec<&str> = server_port.split('-').collect();
+
+            // We've been talking to server as something like 192.168.1.100:554
+            // Just remove the '554' port and replace with response in SETUP
+            let mut server_addr = self.server_addr_rtsp;
+            server_addr.set_port(server_rtp_rtcp[0].parse::<u16>()
+                .expect("[RTSP][parse_setup] Error parsing server_port"));
+
+            self.server_addr_rtp = Some(server_addr);
+        }
+
+        self.id = format!("Session: {}", setup_hash.get("Session")
+            .expect("[RTSP][parse_setup] Error getting Session from hash"));
+    }
+
+    fn parse_stop(&mut self) {
+        match self.response_ok {
+            true  => println!("Shutdown Ok"),
+            false => eprintln!("Shutdown Error"),
+        }
+    }
+}
+
+/// Parses a comma-separated `key="value"` (or `key=value`) challenge
+/// parameter list, as found after the `Digest ` prefix of a
+/// `WWW-Authenticate` header.
+fn parse_auth_params(params: &str) -> HashMap<String, String> {
+    params
+        .split(',')
+        .filter_map(|part| {
+            let (key, value) = part.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().trim_matches('"').to_string()))
+        })
+        .collect()
+}
\ No newline at end of file
@@ -1,13 +1,31 @@
 use anyhow::Result;
 use onvif_cam_rs::client::{Client, Messages};
 use rtsp_rtp_rs::rtp::{Decoders, Rtp};
-use rtsp_rtp_rs::rtsp::{Methods, Rtsp};
+use rtsp_rtp_rs::rtsp::{
+    Credentials, Methods, Rtsp, Transport, INTERLEAVED_CHANNEL_RTCP, INTERLEAVED_CHANNEL_RTP,
+};
 //------------------ SDL2
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 //------------------ Logging
 use log::{debug, info, warn};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Size of every camera's SDL2 texture and grid tile. The textures are
+/// created once, statically (see the `TODO` on `create_texture_static` in
+/// `run_tiled_display`), so every tile shares one size rather than each
+/// camera reporting its own.
+const TILE_WIDTH: u32 = 640;
+const TILE_HEIGHT: u32 = 352;
+
+/// How long to wait for a camera task's in-flight RTSP `Teardown` to finish
+/// after asking it to shut down, before giving up on it.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -27,18 +45,114 @@ async fn main() -> Result<()> {
     let _ = onvif_client.send(Messages::Profiles, 1).await?;
     let cam_uri_02 = onvif_client.send(Messages::GetStreamURI, 1).await?;
 
-    let _ = get_camera_stream(cam_uri_01.as_str()).await?;
-    let _ = get_camera_stream(cam_uri_02.as_str()).await?;
+    let transport = transport_from_env();
+    let cam_uris = [cam_uri_01, cam_uri_02];
+
+    // Each camera gets its own RTSP/RTP session, spawned so all cameras
+    // stream concurrently instead of the viewer blocking on one at a time.
+    // `shutdown` tells every task when the viewer window has closed;
+    // `frame_tx`/`frame_rx` carry decoded frames back to the single SDL2
+    // render loop, which owns the window and so must stay on this task.
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut frame_rxs = Vec::with_capacity(cam_uris.len());
+    let mut handles: Vec<JoinHandle<Result<()>>> = Vec::with_capacity(cam_uris.len());
+
+    for (cam_index, rtsp_uri) in cam_uris.into_iter().enumerate() {
+        let (frame_tx, frame_rx) = mpsc::channel(4);
+        let credentials = credentials_from_env(cam_index);
+        let shutdown_rx = shutdown_rx.clone();
+
+        handles.push(tokio::spawn(stream_camera(
+            rtsp_uri,
+            transport,
+            credentials,
+            cam_index,
+            frame_tx,
+            shutdown_rx,
+        )));
+        frame_rxs.push(frame_rx);
+    }
+
+    run_tiled_display(frame_rxs).await?;
+
+    // Tell every camera task to tear down, then join them with a timeout
+    // instead of aborting - abort() would cancel a task mid-`Teardown`
+    // send, racing the RTSP session closing cleanly against the process
+    // exiting.
+    let _ = shutdown_tx.send(true);
+    for (cam_index, handle) in handles.into_iter().enumerate() {
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, handle).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => warn!("[cam {cam_index}] stream task ended with error: {e}"),
+            Ok(Err(e)) => warn!("[cam {cam_index}] stream task panicked: {e}"),
+            Err(_) => warn!(
+                "[cam {cam_index}] stream task did not shut down within {SHUTDOWN_TIMEOUT:?}"
+            ),
+        }
+    }
 
     Ok(())
 }
 
-async fn get_camera_stream(rtsp_uri: &str) -> Result<()> {
-    info!("----------------------- OPEN CAMERA STREAM! ----------------------");
+/// Selects UDP vs interleaved RTP/RTCP transport. Interleaved is the safer
+/// default since it rides the existing RTSP TCP connection and works behind
+/// NAT/firewalls that drop the extra UDP ports; set `NVR_TRANSPORT=udp` for
+/// cameras/networks where a dedicated UDP pair is preferred.
+fn transport_from_env() -> Transport {
+    match std::env::var("NVR_TRANSPORT") {
+        Ok(val) if val.eq_ignore_ascii_case("udp") => Transport::Udp,
+        _ => Transport::Interleaved,
+    }
+}
+
+/// Reads `CAM_<index>_USERNAME`/`CAM_<index>_PASSWORD`, alongside the
+/// `CAM_<index>_RTSP_URI` the stand-in Onvif client already uses. Most real
+/// cameras require auth on DESCRIBE/SETUP, so this is unset only for open
+/// test streams.
+fn credentials_from_env(cam_index: usize) -> Option<Credentials> {
+    let username = std::env::var(format!("CAM_{cam_index}_USERNAME")).ok()?;
+    let password = std::env::var(format!("CAM_{cam_index}_PASSWORD")).unwrap_or_default();
+    Some(Credentials { username, password })
+}
+
+/// Reads `CAM_<index>_RECORD_PATH`; unset means live view only, no
+/// archival MP4.
+fn recording_path_from_env(cam_index: usize) -> Option<PathBuf> {
+    std::env::var(format!("CAM_{cam_index}_RECORD_PATH"))
+        .ok()
+        .map(PathBuf::from)
+}
+
+/// One decoded frame, copied out of the decoder's internal buffers so it
+/// can be handed across the channel to the render loop - `DecodedYUV`
+/// itself borrows from the `Rtp`'s decoder and can't outlive a single
+/// `try_decode` call.
+struct Frame {
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+    y_stride: usize,
+    u_stride: usize,
+    v_stride: usize,
+}
+
+/// Runs one camera's RTSP session end to end: handshake, RTP/RTCP receive
+/// and decode loop, and teardown. Decoded frames are sent to `frame_tx` for
+/// the render loop to display; `shutdown` is watched so the loop - and the
+/// `Teardown` send after it - can exit promptly once the viewer closes.
+async fn stream_camera(
+    rtsp_uri: String,
+    transport: Transport,
+    credentials: Option<Credentials>,
+    cam_index: usize,
+    frame_tx: mpsc::Sender<Frame>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!("[cam {cam_index}] ----------------------- OPEN CAMERA STREAM! ----------------------");
 
     // If using IP cams, this can be discovered via Onvif
     // if the camera supports it
-    let mut rtsp = Rtsp::new(&rtsp_uri, None).await?;
+    let mut rtsp = Rtsp::new(&rtsp_uri, None, transport, credentials).await?;
 
     rtsp.send(Methods::Options)
         .await?
@@ -53,81 +167,117 @@ async fn get_camera_stream(rtsp_uri: &str) -> Result<()> {
         // Bind address will default to "0.0.0.0"
         // Bind port was defined in RTSP 'SETUP' command
 
-        let mut rtp_stream =
-            Rtp::new(None, rtsp.client_port_rtp, rtsp.server_addr_rtp.unwrap()).await?;
+        let mut rtp_stream = match transport {
+            Transport::Udp => {
+                Rtp::new(None, rtsp.client_port_rtp, rtsp.server_addr_rtp.unwrap()).await?
+            }
+            Transport::Interleaved => {
+                Rtp::new_interleaved(
+                    rtsp.interleaved_handle(),
+                    INTERLEAVED_CHANNEL_RTP,
+                    INTERLEAVED_CHANNEL_RTCP,
+                )
+                .await?
+            }
+        };
         rtp_stream.connect(Decoders::OpenH264).await?;
 
-        // NOTE: Display decoded images with SDL2
-        let sdl_context = sdl2::init().expect("Error sdl2 init");
-        let video_subsystem = sdl_context.video().expect("Error sld2 video subsystem");
+        // Anchors the camera's RTP-clock presentation time (from RTCP
+        // Sender Reports) to this task's monotonic clock at the first
+        // paced frame, so later frames sleep based on elapsed time *since
+        // that anchor* rather than against the camera's absolute wall
+        // clock. Comparing absolute clocks directly either never sleeps
+        // (camera and local clocks agree, so the target is always already
+        // past) or sleeps by a fixed skew every frame (clocks disagree),
+        // which backs up the receive loop without bound.
+        let mut pacing_anchor: Option<(SystemTime, Instant)> = None;
 
-        let window = video_subsystem
-            .window("IP Camera Video", 640, 352)
-            .position_centered()
-            .opengl()
-            .build()?;
+        // Cameras that don't send SPS/PPS in-band (e.g. Axis with "PS
+        // Enabled" off) only carry them in DESCRIBE's SDP.
+        if let Some((sps, pps)) = &rtsp.sprop_parameter_sets {
+            rtp_stream.prime_parameter_sets(sps, pps);
+        }
+
+        // Archive to MP4 alongside the live view when asked to - live view
+        // + archive simultaneously is the core NVR use case.
+        let mut rtp_stream = match recording_path_from_env(cam_index) {
+            Some(record_path) => rtp_stream.with_recording(record_path),
+            None => rtp_stream,
+        };
 
-        let mut canvas = window.into_canvas().build()?;
-        let texture_creator = canvas.texture_creator();
+        'read_rtp_packets: loop {
+            if *shutdown.borrow() {
+                break 'read_rtp_packets;
+            }
 
-        // TODO: Figure out how to move this into loop
-        // so as not to have to apply static definition
-        let mut texture = texture_creator.create_texture_static(PixelFormatEnum::IYUV, 640, 352)?;
-        let mut event_pump = sdl_context.event_pump().expect("Error sld2 event");
+            tokio::select! {
+                biased;
 
-        // Need this during testing as the first 40 frames
-        // or so are blank because it's not starting from SPS
-        // and instead getting frames from mid stream
-        let mut wait_frames = 0;
+                _ = shutdown.changed() => break 'read_rtp_packets,
 
-        'read_rtp_packets: loop {
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'read_rtp_packets,
-                    _ => {}
+                result = rtp_stream.get_rtp() => {
+                    result?;
                 }
             }
 
-            rtp_stream.get_rtp().await?;
-
-            let maybe_some_yuv = rtp_stream.try_decode();
-            match maybe_some_yuv {
-                Ok(some_yuv) => match some_yuv {
-                    Some(yuv) => {
-                        debug!("Decoded YUV!");
-
-                        let (y_size, u_size, v_size) = yuv.strides_yuv();
-                        let _result = texture.update_yuv(
-                            None,
-                            yuv.y_with_stride(),
-                            y_size,
-                            yuv.u_with_stride(),
-                            u_size,
-                            yuv.v_with_stride(),
-                            v_size,
-                        );
-
-                        canvas.clear();
-                        canvas
-                            .copy(&texture, None, None)
-                            .expect("Error copying texture");
-                        canvas.present();
+            match rtp_stream.try_decode() {
+                Ok(Some(yuv)) => {
+                    debug!("[cam {cam_index}] Decoded YUV!");
+
+                    let (y_stride, u_stride, v_stride) = yuv.strides_yuv();
+                    let frame = Frame {
+                        y: yuv.y_with_stride().to_vec(),
+                        u: yuv.u_with_stride().to_vec(),
+                        v: yuv.v_with_stride().to_vec(),
+                        y_stride,
+                        u_stride,
+                        v_stride,
+                    };
+
+                    // Pace display to the camera's intended presentation
+                    // time (from RTCP Sender Reports) rather than
+                    // flushing frames as fast as they decode, once we
+                    // have an SR to map the RTP clock onto wall time.
+                    // See `pacing_anchor` above for why this is relative
+                    // rather than an absolute-clock comparison.
+                    if let Some(rtp_timestamp) = rtp_stream.last_rtp_timestamp() {
+                        if let Some(presentation_time) = rtp_stream.presentation_time(rtp_timestamp) {
+                            let now = Instant::now();
+                            let &mut (anchor_presentation, anchor_instant) =
+                                pacing_anchor.get_or_insert((presentation_time, now));
+
+                            if let Ok(elapsed_since_anchor) =
+                                presentation_time.duration_since(anchor_presentation)
+                            {
+                                if let Some(remaining) =
+                                    (anchor_instant + elapsed_since_anchor).checked_duration_since(now)
+                                {
+                                    tokio::time::sleep(remaining).await;
+                                }
+                            }
+                        }
+                    }
+
+                    // The render loop dropping its receiver (viewer
+                    // closed) is exactly the signal to stop - the
+                    // `shutdown` watch above should already be catching
+                    // this, but a closed channel means the same thing.
+                    if frame_tx.send(frame).await.is_err() {
+                        break 'read_rtp_packets;
                     }
-                    None => debug!("Unable to decode to YUV"),
-                },
+                }
+                Ok(None) => debug!("[cam {cam_index}] Unable to decode to YUV"),
                 // Errors from OpenH264-rs have been useless as they are mostly
                 // native errors passed from C implementation and then propogated
                 // to Rust as a single i64 code and I couldn't find anywhere to
                 // convert this i64 code to it's description...
                 // Instead, I had to use ffprobe after saving out a large raw
                 // stream of decoded packets to file
-                Err(e) => warn!("Error: {e}"),
+                Err(e) => warn!("[cam {cam_index}] Error: {e}"),
             }
         }
+
+        rtp_stream.finish_recording()?;
     }
 
     #[rustfmt::skip]
@@ -136,7 +286,96 @@ async fn get_camera_stream(rtsp_uri: &str) -> Result<()> {
         .await?
         .response_ok;
 
-    info!("Stopping RTSP: {}", is_ok);
+    info!("[cam {cam_index}] Stopping RTSP: {}", is_ok);
+
+    Ok(())
+}
+
+/// Single SDL2 render loop compositing every camera's latest frame into a
+/// `ceil(sqrt(N))` grid, one cell per camera. Owns the window, so this is
+/// the only place touching SDL2 - camera tasks only ever produce frames.
+async fn run_tiled_display(mut frame_rxs: Vec<mpsc::Receiver<Frame>>) -> Result<()> {
+    let grid_dim = (frame_rxs.len() as f64).sqrt().ceil() as u32;
+
+    let sdl_context = sdl2::init().expect("Error sdl2 init");
+    let video_subsystem = sdl_context.video().expect("Error sld2 video subsystem");
+
+    let window = video_subsystem
+        .window(
+            "IP Camera Video",
+            TILE_WIDTH * grid_dim,
+            TILE_HEIGHT * grid_dim,
+        )
+        .position_centered()
+        .opengl()
+        .build()?;
+
+    let mut canvas = window.into_canvas().build()?;
+    let texture_creator = canvas.texture_creator();
+
+    // TODO: Figure out how to move this into loop
+    // so as not to have to apply static definition
+    let mut textures = frame_rxs
+        .iter()
+        .map(|_| texture_creator.create_texture_static(PixelFormatEnum::IYUV, TILE_WIDTH, TILE_HEIGHT))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut event_pump = sdl_context.event_pump().expect("Error sld2 event");
+
+    'read_rtp_packets: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'read_rtp_packets,
+                _ => {}
+            }
+        }
+
+        for (cam_index, frame_rx) in frame_rxs.iter_mut().enumerate() {
+            // Drain down to whatever's latest - display cares about the
+            // newest frame, not every frame a camera produced while we
+            // were busy rendering the others.
+            let mut latest = None;
+            while let Ok(frame) = frame_rx.try_recv() {
+                latest = Some(frame);
+            }
+
+            if let Some(frame) = latest {
+                let _result = textures[cam_index].update_yuv(
+                    None,
+                    &frame.y,
+                    frame.y_stride,
+                    &frame.u,
+                    frame.u_stride,
+                    &frame.v,
+                    frame.v_stride,
+                );
+            }
+        }
+
+        canvas.clear();
+        for (cam_index, texture) in textures.iter().enumerate() {
+            let col = cam_index as u32 % grid_dim;
+            let row = cam_index as u32 / grid_dim;
+            let cell = Rect::new(
+                (col * TILE_WIDTH) as i32,
+                (row * TILE_HEIGHT) as i32,
+                TILE_WIDTH,
+                TILE_HEIGHT,
+            );
+            canvas
+                .copy(texture, None, cell)
+                .expect("Error copying texture");
+        }
+        canvas.present();
+
+        // Rendering faster than cameras can possibly produce new frames
+        // would just burn CPU re-drawing the same textures.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
 
     Ok(())
 }